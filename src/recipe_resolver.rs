@@ -1,7 +1,68 @@
 use crate::common::*;
 
+use std::collections::BTreeMap;
+
 use CompilationErrorKind::*;
 
+/// Partition `recipes` into layers suitable for parallel execution: every
+/// recipe in a layer depends only on recipes in strictly earlier layers, so
+/// an executor may run all the recipes in a layer concurrently once every
+/// earlier layer has finished successfully.
+///
+/// This is Kahn's algorithm, peeling off recipes with no unmet dependencies
+/// one whole layer at a time. `resolve_recipes` already proves the
+/// dependency graph is acyclic, so every recipe is guaranteed to end up in
+/// some layer.
+pub(crate) fn dependency_layers<'a>(
+  recipes: &Table<'a, Rc<Recipe<'a>>>,
+) -> Vec<Vec<Rc<Recipe<'a>>>> {
+  let mut unmet = BTreeMap::new();
+  let mut successors: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+
+  for recipe in recipes.values() {
+    unmet.insert(recipe.name(), recipe.dependencies.len());
+
+    for dependency in &recipe.dependencies {
+      successors
+        .entry(dependency.recipe.name())
+        .or_insert_with(Vec::new)
+        .push(recipe.name());
+    }
+  }
+
+  let mut ready = unmet
+    .iter()
+    .filter(|&(_, &count)| count == 0)
+    .map(|(&name, _)| name)
+    .collect::<Vec<&str>>();
+
+  let mut layers = Vec::new();
+
+  while !ready.is_empty() {
+    let layer = ready
+      .iter()
+      .map(|name| recipes.get(name).unwrap().clone())
+      .collect();
+
+    let mut next_ready = Vec::new();
+
+    for name in &ready {
+      for dependent in successors.get(name).unwrap_or(&Vec::new()) {
+        let unmet_count = unmet.get_mut(dependent).unwrap();
+        *unmet_count -= 1;
+        if *unmet_count == 0 {
+          next_ready.push(*dependent);
+        }
+      }
+    }
+
+    layers.push(layer);
+    ready = next_ready;
+  }
+
+  layers
+}
+
 pub(crate) struct RecipeResolver<'a: 'b, 'b> {
   unresolved_recipes: Table<'a, Recipe<'a, Name<'a>>>,
   resolved_recipes: Table<'a, Rc<Recipe<'a>>>,
@@ -47,6 +108,17 @@ impl<'a, 'b> RecipeResolver<'a, 'b> {
           }
         }
       }
+
+      for dependency in recipe.dependencies.iter().chain(&recipe.subsequents) {
+        for argument in &dependency.arguments {
+          for (function, argc) in argument.functions() {
+            Function::resolve(&function, argc)?;
+          }
+          for variable in argument.variables() {
+            resolver.resolve_variable(&variable, &recipe.parameters)?;
+          }
+        }
+      }
     }
 
     Ok(resolver.resolved_recipes)
@@ -79,56 +151,108 @@ impl<'a, 'b> RecipeResolver<'a, 'b> {
 
     stack.push(recipe.name());
 
+    let dependencies = self.resolve_dependencies(stack, &recipe, &recipe.dependencies)?;
+    let subsequents = self.resolve_dependencies(stack, &recipe, &recipe.subsequents)?;
+
+    let resolved = Rc::new(recipe.resolve(dependencies, subsequents));
+    self.resolved_recipes.insert(resolved.clone());
+    stack.pop();
+    Ok(resolved)
+  }
+
+  // Resolve a list of unresolved dependencies, used for both the
+  // before-the-body `dependencies` and the after-the-body `subsequents`.
+  // Both lists push onto the same `stack`, so `a: && a` is caught by
+  // `CircularRecipeDependency` just like `a: a`.
+  fn resolve_dependencies(
+    &mut self,
+    stack: &mut Vec<&'a str>,
+    recipe: &Recipe<'a, Name<'a>>,
+    unresolved_dependencies: &[UnresolvedDependency<'a>],
+  ) -> CompilationResult<'a, Vec<Dependency<'a>>> {
     let mut dependencies: Vec<Dependency> = Vec::new();
-    for dependency in &recipe.dependencies {
-      let name = dependency.lexeme();
 
-      if let Some(resolved) = self.resolved_recipes.get(name) {
-        // dependency already resolved
-        if !resolved.parameters.is_empty() {
-          return Err(dependency.error(DependencyHasParameters {
-            recipe: recipe.name(),
-            dependency: name,
-          }));
-        }
+    for dependency in unresolved_dependencies {
+      let name = dependency.recipe.lexeme();
 
-        dependencies.push(Dependency(resolved.clone()));
+      let resolved = if let Some(resolved) = self.resolved_recipes.get(name) {
+        // dependency already resolved
+        resolved.clone()
       } else if stack.contains(&name) {
         let first = stack[0];
         stack.push(first);
         return Err(
-          dependency.error(CircularRecipeDependency {
+          dependency.recipe.error(CircularRecipeDependency {
             recipe: recipe.name(),
             circle: stack
               .iter()
-              .skip_while(|name| **name != dependency.lexeme())
+              .skip_while(|name| **name != dependency.recipe.lexeme())
               .cloned()
               .collect(),
           }),
         );
       } else if let Some(unresolved) = self.unresolved_recipes.remove(name) {
         // resolve unresolved dependency
-        if !unresolved.parameters.is_empty() {
-          return Err(dependency.error(DependencyHasParameters {
-            recipe: recipe.name(),
-            dependency: name,
-          }));
-        }
-
-        dependencies.push(Dependency(self.resolve_recipe(stack, unresolved)?));
+        self.resolve_recipe(stack, unresolved)?
       } else {
         // dependency is unknown
-        return Err(dependency.error(UnknownDependency {
+        return Err(dependency.recipe.error(UnknownDependency {
           recipe: recipe.name(),
           unknown: name,
         }));
-      }
+      };
+
+      let arguments = Self::resolve_dependency_arguments(recipe, dependency, &resolved)?;
+
+      dependencies.push(Dependency {
+        recipe: resolved,
+        arguments,
+      });
     }
 
-    let resolved = Rc::new(recipe.resolve(dependencies));
-    self.resolved_recipes.insert(resolved.clone());
-    stack.pop();
-    Ok(resolved)
+    Ok(dependencies)
+  }
+
+  fn resolve_dependency_arguments(
+    recipe: &Recipe<'a, Name<'a>>,
+    dependency: &UnresolvedDependency<'a>,
+    target: &Recipe<'a>,
+  ) -> CompilationResult<'a, Vec<Expression<'a>>> {
+    let found = dependency.arguments.len();
+
+    let min = target
+      .parameters
+      .iter()
+      .filter(|parameter| !parameter.variadic && parameter.default.is_none())
+      .count();
+
+    let max = if target.parameters.iter().any(|parameter| parameter.variadic) {
+      usize::MAX
+    } else {
+      target.parameters.len()
+    };
+
+    if found < min {
+      return Err(dependency.recipe.error(DependencyArgumentCountMismatch {
+        recipe: recipe.name(),
+        dependency: dependency.recipe.lexeme(),
+        found,
+        min,
+        max,
+      }));
+    }
+
+    if found > max {
+      return Err(dependency.recipe.error(DependencyArgumentCountMismatch {
+        recipe: recipe.name(),
+        dependency: dependency.recipe.lexeme(),
+        found,
+        min,
+        max,
+      }));
+    }
+
+    Ok(dependency.arguments.clone())
   }
 }
 
@@ -156,6 +280,16 @@ mod tests {
     kind:   CircularRecipeDependency{recipe: "a", circle: vec!["a", "a"]},
   }
 
+  analysis_error! {
+    name:   subsequent_self_dependency,
+    input:  "a: && a",
+    offset: 6,
+    line:   0,
+    column: 6,
+    width:  1,
+    kind:   CircularRecipeDependency{recipe: "a", circle: vec!["a", "a"]},
+  }
+
   analysis_error! {
     name:   unknown_dependency,
     input:  "a: b",
@@ -166,6 +300,26 @@ mod tests {
     kind:   UnknownDependency{recipe: "a", unknown: "b"},
   }
 
+  analysis_error! {
+    name:   dependency_argument_count_too_many,
+    input:  "b:\na: (b 1)",
+    offset: 7,
+    line:   1,
+    column: 4,
+    width:  1,
+    kind:   DependencyArgumentCountMismatch{recipe: "a", dependency: "b", found: 1, min: 0, max: 0},
+  }
+
+  analysis_error! {
+    name:   dependency_argument_count_too_few,
+    input:  "b arg:\na: (b)",
+    offset: 11,
+    line:   1,
+    column: 4,
+    width:  1,
+    kind:   DependencyArgumentCountMismatch{recipe: "a", dependency: "b", found: 0, min: 1, max: 1},
+  }
+
   analysis_error! {
     name:   unknown_interpolation_variable,
     input:  "x:\n {{   hello}}",